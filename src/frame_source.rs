@@ -0,0 +1,287 @@
+use anyhow::{anyhow, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_app as gst_app;
+use gstreamer_video as gst_video;
+use opencv::core::{self, Mat, Mat_AUTO_STEP, CV_8UC3};
+use opencv::imgcodecs;
+use std::ffi::c_void;
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// What payload `FrameSource` hands back per frame.
+pub enum FrameEncoding {
+    /// JPEG-encode each frame before handing it to the consumer. Suited to
+    /// consumers that ship frames off-process (e.g. over a network) cheaply.
+    Jpeg,
+    /// Hand back the raw BGR bytes pulled straight off the appsink, with no
+    /// lossy encode/decode round trip. Suited to same-process consumers that
+    /// are just going to build another `Mat` out of the bytes anyway.
+    Raw,
+}
+
+/// A frame pulled off the appsink, along with the dimensions needed to
+/// reinterpret `data` (JPEG bytes or tightly packed raw BGR, depending on
+/// the `FrameEncoding` the `FrameSource` was built with).
+pub struct Frame {
+    pub width: i32,
+    pub height: i32,
+    pub data: Vec<u8>,
+}
+
+/// A reusable, GStreamer-backed source of video frames.
+///
+/// `FrameSource` owns the pipeline end to end: build it from a gst-launch
+/// style pipeline description ending in `appsink name=sink`, then iterate it
+/// like a collection of frames. Each item is a frame pulled from the sink
+/// and wrapped in a `Mat`, so downstream code can consume frames without
+/// holding onto any GStreamer state itself. Depending on the `FrameEncoding`
+/// passed to `new`, the frame is either JPEG-encoded (a lossless codec's
+/// extra CPU cost on the GStreamer streaming thread would otherwise add
+/// directly to capture latency) or handed back as raw BGR bytes.
+///
+/// Internally the sink is still configured with `drop=true` / `max-buffers=1`
+/// / `sync=false`, so iterating always yields the freshest frame rather than
+/// one queued up behind a slow consumer.
+pub struct FrameSource {
+    pipeline: gst::Pipeline,
+    frames: Receiver<Result<Option<Frame>>>,
+    /// Signaled once by the bus-watcher thread (see `new`) when it has seen
+    /// EOS/Error (or the bus itself closed), so `Drop` can wait for a clean
+    /// shutdown without also reading the bus itself.
+    shutdown: Receiver<()>,
+}
+
+impl FrameSource {
+    /// Builds and starts `pipeline_desc`, requesting BGR frames from the
+    /// appsink named `sink` at `size` (width, height) where the source
+    /// supports it.
+    ///
+    /// `size` is only a preference, not a hard requirement: it's merged as a
+    /// fallback with a format-only caps structure, so sources that can't
+    /// match it exactly (e.g. a 1080p RTSP/IP camera while running at the
+    /// local camera's 640x480 default) still negotiate instead of failing to
+    /// link. The actual negotiated size is read back per-frame from the
+    /// sample's caps rather than assumed to be `size`.
+    pub fn new(pipeline_desc: &str, size: (i32, i32), encoding: FrameEncoding) -> Result<Self> {
+        let pipeline = gst::parse::launch(pipeline_desc)?
+            .dynamic_cast::<gst::Pipeline>()
+            .map_err(|_| anyhow!("Expected a Pipeline"))?;
+
+        let sink = pipeline
+            .by_name("sink")
+            .ok_or_else(|| anyhow!("Sink element not found"))?
+            .dynamic_cast::<gst_app::AppSink>()
+            .map_err(|_| anyhow!("Sink element is not an AppSink"))?;
+
+        let (width, height) = size;
+        let preferred = gst::Caps::builder("video/x-raw")
+            .field("format", "BGR")
+            .field("width", width)
+            .field("height", height)
+            .build();
+        let fallback = gst::Caps::builder("video/x-raw").field("format", "BGR").build();
+        sink.set_caps(Some(&preferred.merge(fallback)));
+
+        // Keep the freshest frame available rather than letting a slow
+        // consumer fall behind real time.
+        sink.set_drop(true);
+        sink.set_max_buffers(1);
+        sink.set_property("sync", false);
+
+        let (tx, rx) = sync_channel(1);
+
+        let sample_tx = tx;
+        let callbacks = gst_app::AppSinkCallbacks::builder()
+            .new_sample(move |appsink| {
+                let frame = match encoding {
+                    FrameEncoding::Jpeg => Self::pull_encoded(appsink),
+                    FrameEncoding::Raw => Self::pull_raw(appsink),
+                }
+                .map(Some);
+                // Drop the frame rather than blocking the streaming thread if
+                // the consumer hasn't caught up yet.
+                let _ = sample_tx.try_send(frame);
+                Ok(gst::FlowSuccess::Ok)
+            })
+            .build();
+        sink.set_callbacks(callbacks);
+
+        // The appsink callbacks above only ever see frame data - neither a
+        // clean EOS nor a pipeline error (e.g. an RTSP disconnect) reaches
+        // `new_sample`, so without this the consumer's `next()` would block
+        // forever with no diagnostic. A single background thread is the only
+        // reader of the bus: two readers racing `timed_pop_filtered` against
+        // each other can each steal the other's message (it discards
+        // everything that doesn't match its own filter), which previously
+        // made `Drop`'s EOS wait below block for its full timeout.
+        let bus = pipeline.bus().ok_or_else(|| anyhow!("Pipeline has no bus"))?;
+        let (shutdown_tx, shutdown_rx) = sync_channel(1);
+        thread::spawn(move || {
+            let terminal = match bus
+                .timed_pop_filtered(gst::ClockTime::NONE, &[gst::MessageType::Eos, gst::MessageType::Error])
+                .as_ref()
+                .map(gst::Message::view)
+            {
+                Some(gst::MessageView::Error(err)) => Err(anyhow!(
+                    "pipeline error from {:?}: {} ({:?})",
+                    err.src().map(|s| s.path_string()),
+                    err.error(),
+                    err.debug()
+                )),
+                // Eos, or the bus closed because the pipeline was dropped.
+                _ => Ok(None),
+            };
+            // Unlike frames, this terminal item must never be silently
+            // dropped - block (briefly, until the consumer drains whatever
+            // frame is already queued) rather than `try_send`, otherwise a
+            // finite source would hang at end of stream instead of ending
+            // the iterator.
+            let _ = tx.send(terminal);
+            let _ = shutdown_tx.send(());
+        });
+
+        pipeline.set_state(gst::State::Playing)?;
+
+        Ok(Self {
+            pipeline,
+            frames: rx,
+            shutdown: shutdown_rx,
+        })
+    }
+
+    /// Pulls one sample from `appsink`, wraps it in a `Mat` and JPEG-encodes it.
+    fn pull_encoded(appsink: &gst_app::AppSink) -> Result<Frame> {
+        let sample = appsink.pull_sample()?;
+        with_mat_from_sample(&sample, |width, height, bgr| {
+            let mut encoded = core::Vector::new();
+            imgcodecs::imencode(".jpg", bgr, &mut encoded, &core::Vector::new())?;
+            Ok(Frame {
+                width,
+                height,
+                data: encoded.to_vec(),
+            })
+        })
+    }
+
+    /// Pulls one sample from `appsink` and hands back its raw BGR bytes,
+    /// with no encode/decode round trip.
+    ///
+    /// `Mat::try_clone` is used (rather than copying out of the mapped
+    /// buffer by hand) because OpenCV guarantees a cloned `Mat` is always
+    /// tightly packed, so the caller can reinterpret `data` as
+    /// `width * height * 3` bytes without re-deriving GStreamer's stride.
+    fn pull_raw(appsink: &gst_app::AppSink) -> Result<Frame> {
+        let sample = appsink.pull_sample()?;
+        with_mat_from_sample(&sample, |width, height, bgr| {
+            let owned = bgr.try_clone()?;
+            Ok(Frame {
+                width,
+                height,
+                data: owned.data_bytes()?.to_vec(),
+            })
+        })
+    }
+}
+
+/// Wraps the buffer of `sample` in a BGR `Mat` and hands it, along with its
+/// negotiated width/height, to `f`.
+///
+/// GStreamer does not guarantee tightly packed rows (buffers are often
+/// padded to an alignment boundary), so the real plane stride is pulled out
+/// of the negotiated video info and used as the Mat step, falling back to
+/// `Mat_AUTO_STEP` only when it matches `width * channels`.
+pub(crate) fn with_mat_from_sample<R>(
+    sample: &gst::Sample,
+    f: impl FnOnce(i32, i32, &Mat) -> Result<R>,
+) -> Result<R> {
+    let buffer = sample.buffer().ok_or_else(|| anyhow!("No buffer in sample"))?;
+    let map = buffer.map_readable()?;
+
+    let caps = sample.caps().ok_or_else(|| anyhow!("No caps in sample"))?;
+    let s = caps.structure(0).ok_or_else(|| anyhow!("No structure in caps"))?;
+
+    let width: i32 = s.get("width")?;
+    let height: i32 = s.get("height")?;
+
+    let video_info = gst_video::VideoInfo::from_caps(&caps)?;
+    let stride = video_info.stride()[0] as usize;
+    let step = if stride == width as usize * 3 {
+        Mat_AUTO_STEP
+    } else {
+        stride
+    };
+
+    // Create a Mat that references the GStreamer buffer
+    // SAFETY: We are creating a Mat view into GStreamer's memory buffer.
+    // This is safe as long as `bgr` is not used after `map` goes out of scope,
+    // which `f` cannot outlive since it only borrows `bgr`.
+    let bgr = unsafe {
+        Mat::new_rows_cols_with_data_unsafe(
+            height,
+            width,
+            CV_8UC3,
+            map.as_ptr() as *mut c_void,
+            step,
+        )?
+    };
+
+    f(width, height, &bgr)
+}
+
+impl Iterator for FrameSource {
+    type Item = Result<Option<Frame>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.frames.recv().ok()
+    }
+}
+
+/// A lightweight, `Send` handle that can request a `FrameSource` to stop
+/// while the `FrameSource` itself is busy being iterated.
+#[derive(Clone)]
+pub struct FrameSourceHandle(gst::Pipeline);
+
+impl FrameSourceHandle {
+    /// Requests a graceful shutdown: sends EOS through the pipeline so any
+    /// downstream muxer (e.g. `mp4mux` on a `--record` branch) flushes and
+    /// finalizes its output before the owning `FrameSource` tears down. Can be
+    /// called from another thread, e.g. a Ctrl+C handler, to end an
+    /// otherwise-infinite session.
+    pub fn request_stop(&self) {
+        let _ = self.0.send_event(gst::event::Eos::new());
+    }
+}
+
+impl FrameSource {
+    /// Returns a handle that can request this source to stop from elsewhere,
+    /// e.g. a Ctrl+C handler running while the source is being iterated.
+    pub fn handle(&self) -> FrameSourceHandle {
+        FrameSourceHandle(self.pipeline.clone())
+    }
+}
+
+impl Drop for FrameSource {
+    fn drop(&mut self) {
+        // Give the pipeline a chance to flush EOS through to any muxer
+        // before going to NULL, otherwise e.g. mp4mux never writes its moov
+        // atom and the recorded file is truncated/unplayable. Wait on the
+        // bus-watcher thread's shutdown signal rather than reading the bus
+        // here directly - this is the only other place that cares about
+        // Eos/Error, so it must not run a second, racing bus consumer.
+        let _ = self.pipeline.send_event(gst::event::Eos::new());
+        // The consumer has stopped iterating, so drain any queued frames
+        // here; otherwise the bus-watcher's blocking send of its terminal
+        // item never completes and the wait runs to its full timeout.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            while self.frames.try_recv().is_ok() {}
+            match self.shutdown.recv_timeout(Duration::from_millis(10)) {
+                Err(RecvTimeoutError::Timeout) if Instant::now() < deadline => continue,
+                _ => break,
+            }
+        }
+        let _ = self.pipeline.set_state(gst::State::Null);
+    }
+}
@@ -1,11 +1,76 @@
+mod frame_source;
+mod thumbnail;
+
 use anyhow::Result;
+use frame_source::{FrameEncoding, FrameSource};
 use gstreamer as gst;
-use gstreamer::prelude::*;
-use gstreamer_app as gst_app;
-use opencv::core::{self, CV_8UC3, Mat, Mat_AUTO_STEP};
+use opencv::core::{self, Mat_AUTO_STEP, CV_8UC3};
 use opencv::{highgui, imgcodecs, imgproc};
 use std::ffi::c_void;
 
+/// Pulls the value following `flag` out of the raw argument list, e.g. `--rtsp <uri>`.
+fn arg_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
+
+/// Parses a `WIDTHxHEIGHT` size string, e.g. `320x240`.
+fn parse_size(s: &str) -> Option<(i32, i32)> {
+    let (w, h) = s.split_once('x')?;
+    Some((w.parse().ok()?, h.parse().ok()?))
+}
+
+/// Builds the appsink branch of the pipeline, optionally tee'd off to a
+/// second branch that records the raw stream to `record_path` (e.g. as MP4)
+/// while frames are still pulled from the appsink for processing.
+fn build_sink_branch(record_path: Option<&str>) -> String {
+    match record_path {
+        // The record branch's queue is leaky so a slow x264enc/mp4mux drops
+        // old buffers instead of filling up and back-pressuring the tee,
+        // which would otherwise stall the appsink branch too.
+        Some(path) => format!(
+            "tee name=t ! queue ! appsink name=sink \
+             t. ! queue leaky=downstream max-size-buffers=30 ! videoconvert ! x264enc tune=zerolatency ! mp4mux ! filesink location={path}"
+        ),
+        None => "appsink name=sink".to_string(),
+    }
+}
+
+/// Builds the gst-launch style pipeline description for the selected source.
+///
+/// `--rtsp`/`--uri` select a network camera (RTSP over H264) or a generic
+/// file/HTTP source respectively. Without either flag we fall back to the
+/// local Pi camera, trying libcamera first and v4l2-era rpicamsrc second.
+/// `--record <path>` additionally archives the raw stream to disk via a tee
+/// while frames keep flowing to the appsink for processing.
+fn build_pipeline_desc(args: &[String]) -> Result<String> {
+    let record_path = arg_value(args, "--record");
+    let sink_branch = build_sink_branch(record_path.as_deref());
+
+    if let Some(uri) = arg_value(args, "--rtsp") {
+        return Ok(format!(
+            "rtspsrc location={uri} latency=0 ! rtph264depay ! avdec_h264 ! videoconvert ! video/x-raw,format=BGR ! {sink_branch}"
+        ));
+    }
+
+    if let Some(uri) = arg_value(args, "--uri") {
+        return Ok(format!("uridecodebin uri={uri} ! videoconvert ! {sink_branch}"));
+    }
+
+    // Hardware setup. Try libcamera first, fallback to v4l2 for older OS.
+    let pipeline_desc = format!(
+        "libcamerasrc ! videoconvert ! video/x-raw,format=BGR,width=640,height=480 ! {sink_branch}"
+    );
+    if gst::parse::launch(&pipeline_desc).is_ok() {
+        return Ok(pipeline_desc);
+    }
+    Ok(format!(
+        "rpicamsrc ! videoconvert ! video/x-raw,format=BGR,width=640,height=480 ! {sink_branch}"
+    ))
+}
+
 fn main() -> Result<()> {
     // Parse command line args
     let args: Vec<String> = std::env::args().collect();
@@ -18,61 +83,52 @@ fn main() -> Result<()> {
         highgui::named_window("Camera Capture", highgui::WINDOW_AUTOSIZE)?; // While sshing -X flag is needed and enabled X11 forwarding
     }
 
-    // Hardware setup. Try libcamera first, fallback to v4l2 for older OS.
-    let pipeline_desc = "libcamerasrc ! videoconvert ! video/x-raw,format=BGR,width=640,height=480 ! appsink name=sink";
-    let pipeline = match gst::parse::launch(pipeline_desc) {
-        Ok(p) => p,
-        Err(_) => gst::parse::launch(
-            "rpicamsrc ! videoconvert ! video/x-raw,format=BGR,width=640,height=480 ! appsink name=sink",
-        )?,
-    };
-
-    let pipeline = pipeline
-        .dynamic_cast::<gst::Pipeline>()
-        .expect("Expected a Pipeline");
-
-    // Bridge between GStreamer and program. Raw frames can be pulled from it.
-    let sink = pipeline
-        .by_name("sink")
-        .expect("Sink element not found")
-        .dynamic_cast::<gst_app::AppSink>()
-        .expect("Sink element is not an AppSink");
-
-    // Starts camera capture
-    pipeline.set_state(gst::State::Playing)?;
-
-    loop {
-        // Polling for next frame
-        let sample = match sink.pull_sample() {
-            Ok(s) => s,
-            Err(_) => continue,
-        };
+    let pipeline_desc = build_pipeline_desc(&args)?;
 
-        let buffer = sample.buffer().expect("No buffer in sample");
-        let map = buffer.map_readable().expect("Failed to map buffer");
+    // Single-frame grab for file inputs: seek to a timestamp, pull exactly
+    // one (preroll) frame, rescale it, and write it out.
+    if let Some(position) = arg_value(&args, "--thumbnail") {
+        let out_path = arg_value(&args, "--out").unwrap_or_else(|| "/tmp/thumbnail.jpg".to_string());
+        let size = arg_value(&args, "--size")
+            .and_then(|s| parse_size(&s))
+            .unwrap_or((320, 240));
+        return thumbnail::extract_thumbnail(&pipeline_desc, position.parse()?, size, &out_path);
+    }
 
-        let caps = sample.caps().expect("No caps in sample");
-        let s = caps.structure(0).expect("No structure in caps");
+    let size = arg_value(&args, "--size")
+        .and_then(|s| parse_size(&s))
+        .unwrap_or((640, 480));
+    let frame_source = FrameSource::new(&pipeline_desc, size, FrameEncoding::Raw)?;
 
-        let width: i32 = s.get("width")?;
-        let height: i32 = s.get("height")?;
+    // Headless runs (notably `--record`) otherwise loop forever with no way
+    // to end the session cleanly; let Ctrl+C trigger the same EOS-then-NULL
+    // teardown as a normal end of stream so e.g. mp4mux finalizes its output.
+    let stop_handle = frame_source.handle();
+    ctrlc::set_handler(move || stop_handle.request_stop())?;
+
+    for frame in frame_source {
+        let Some(frame) = frame? else {
+            break; // End of stream
+        };
 
-        // Create a Mat that references the GStreamer buffer
-        // Frame is 2D with width x height x channels
-        // SAFETY: We are creating a Mat view into GStreamerâ€™s memory buffer.
-        // This is safe as long as `bgr` is not used after `map` goes out of scope.
+        // Build a Mat straight over the raw BGR bytes rather than going
+        // through imdecode - `FrameEncoding::Raw` frames are already
+        // tightly packed (see `FrameSource::pull_raw`), so the stride is
+        // just width * 3.
+        // SAFETY: `frame.data` outlives `bgr`, which doesn't escape this
+        // loop iteration.
         let bgr = unsafe {
-            Mat::new_rows_cols_with_data_unsafe(
-                height,
-                width,
+            core::Mat::new_rows_cols_with_data_unsafe(
+                frame.height,
+                frame.width,
                 CV_8UC3,
-                map.as_ptr() as *mut c_void,
+                frame.data.as_ptr() as *mut c_void,
                 Mat_AUTO_STEP,
             )?
         };
 
         // Convert to HSV
-        let mut hsv = Mat::default();
+        let mut hsv = core::Mat::default();
         imgproc::cvt_color(&bgr, &mut hsv, imgproc::COLOR_BGR2HSV, 0)?;
 
         // Show or save ever 100 frame
@@ -85,7 +141,6 @@ fn main() -> Result<()> {
         } else {
             // Headless mode: save every 100 frames
             if frame_count % 100 == 0 {
-                println!("Caps: {:?}", s.to_string());
                 let filename = format!("/tmp/frame_{:06}.jpg", frame_count);
                 imgcodecs::imwrite(&filename, &hsv, &core::Vector::new())?;
                 println!("Saved {}", filename);
@@ -94,6 +149,5 @@ fn main() -> Result<()> {
         }
     }
 
-    pipeline.set_state(gst::State::Null)?;
     Ok(())
 }
@@ -0,0 +1,66 @@
+use crate::frame_source::with_mat_from_sample;
+use anyhow::{anyhow, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_app as gst_app;
+use opencv::core::{Mat, Size, Vector};
+use opencv::{imgcodecs, imgproc};
+
+/// Seeks `pipeline_desc` to `position_secs` and grabs exactly one frame,
+/// rescaled to `size` (width, height), writing it to `out_path`.
+///
+/// Rather than running the playback loop, the pipeline is paused, seeked
+/// with `FLUSH | KEY_UNIT` to the target position, and the resulting preroll
+/// sample is pulled straight off the appsink - a fast single-frame grab
+/// without decoding the whole file.
+pub fn extract_thumbnail(
+    pipeline_desc: &str,
+    position_secs: u64,
+    size: (i32, i32),
+    out_path: &str,
+) -> Result<()> {
+    let pipeline = gst::parse::launch(pipeline_desc)?
+        .dynamic_cast::<gst::Pipeline>()
+        .map_err(|_| anyhow!("Expected a Pipeline"))?;
+
+    let sink = pipeline
+        .by_name("sink")
+        .ok_or_else(|| anyhow!("Sink element not found"))?
+        .dynamic_cast::<gst_app::AppSink>()
+        .map_err(|_| anyhow!("Sink element is not an AppSink"))?;
+
+    // `with_mat_from_sample` below unconditionally builds a BGR Mat; without
+    // this the `--uri` pipeline's bare `appsink` would pass the decoder's
+    // native format (commonly I420) straight through and the thumbnail would
+    // come out garbled.
+    sink.set_caps(Some(
+        &gst::Caps::builder("video/x-raw").field("format", "BGR").build(),
+    ));
+
+    pipeline.set_state(gst::State::Paused)?;
+    pipeline.state(gst::ClockTime::NONE).0?;
+
+    pipeline.seek_simple(
+        gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT,
+        gst::ClockTime::from_seconds(position_secs),
+    )?;
+
+    let sample = sink.pull_preroll()?;
+    let resized = with_mat_from_sample(&sample, |_width, _height, bgr| {
+        let mut resized = Mat::default();
+        imgproc::resize(
+            bgr,
+            &mut resized,
+            Size::new(size.0, size.1),
+            0.0,
+            0.0,
+            imgproc::INTER_LINEAR,
+        )?;
+        Ok(resized)
+    })?;
+
+    imgcodecs::imwrite(out_path, &resized, &Vector::new())?;
+
+    pipeline.set_state(gst::State::Null)?;
+    Ok(())
+}